@@ -1,12 +1,15 @@
 // SPDX-License-Identifier: Unlicense
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::convert::TryInto; // Import TryInto for the conversion
+use std::time::Duration;
 
 use crate::luhn::AccountNumber;
+use crate::migration;
 
 use rand::prelude::*;
 use rusqlite::{Connection, Result};
+use sha2::{Digest, Sha256};
 
 
 #[derive(Debug)]
@@ -14,9 +17,26 @@ pub struct Account {
     pub id: u64,
     pub account_number: String,
     pub balance: usize,
+    pub held: usize,
+    pub locked: bool,
+    /// The plaintext PIN. Only ever populated right after account creation —
+    /// storage only ever keeps a salted hash, so accounts loaded back with
+    /// [`fetch_account`] carry an empty string here instead.
     pub pin: String,
 }
 
+/// One row of the `transactions` ledger.
+#[derive(Debug)]
+pub struct Transaction {
+    pub tx_id: i64,
+    pub account_number: String,
+    pub kind: String,
+    pub amount: i64,
+    pub state: String,
+    pub fee: i64,
+    pub denom: String,
+}
+
 impl Account {
     pub fn new() -> Result<Self> {
         let mut new_account_number = AccountNumber::default();
@@ -41,6 +61,13 @@ impl Account {
 
         Ok(account)
     }
+
+    /// Looks up this account's balance in a given denomination (e.g. `"USD"`,
+    /// `"BTC"`), returning `0` if it has never held that denomination.
+    pub fn balance_of(&self, denom: &str) -> Result<i64> {
+        let db = initialise_bankdb()?;
+        get_balance(&db, &self.account_number, denom)
+    }
 }
 
 #[cfg(not(test))]
@@ -54,20 +81,244 @@ fn database_path() -> PathBuf {
 }
 
 pub fn initialise_bankdb() -> Result<Connection> {
-    let db = Connection::open(database_path())?;
+    initialise_bankdb_with_key(None)
+}
 
-    let command = "CREATE TABLE IF NOT EXISTS account(
-id INTEGER PRIMARY KEY,
-account_number TEXT,
-pin TEXT DEFAULT '000000',
-balance INTEGER DEFAULT 0
-)
-";
+/// Like [`initialise_bankdb`], but opts the database into encryption at rest
+/// *if* the linked SQLite is actually SQLCipher. `passphrase` is applied as a
+/// `PRAGMA key` right after opening the connection, so the same passphrase
+/// must be supplied on every subsequent open of that `bank.s3db`. Pass `None`
+/// for the existing plaintext behaviour.
+///
+/// `PRAGMA key` is silently accepted and does nothing on stock SQLite, so a
+/// passphrase only has any effect when this crate is built against
+/// SQLCipher (rusqlite's `bundled-sqlcipher` feature). Rather than pretend
+/// the database got encrypted when it didn't, this checks for SQLCipher via
+/// `PRAGMA cipher_version` and fails instead of silently no-opping.
+pub fn initialise_bankdb_with_key(passphrase: Option<&str>) -> Result<Connection> {
+    let mut db = Connection::open(database_path())?;
+
+    if let Some(passphrase) = passphrase {
+        let cipher_version: rusqlite::Result<String> =
+            db.query_row("PRAGMA cipher_version", [], |row| row.get(0));
+        if cipher_version.is_err() {
+            return Err(rusqlite::Error::QueryReturnedNoRows); // Not SQLCipher; `PRAGMA key` would be a no-op.
+        }
 
-    db.execute(command, ())?;
+        db.pragma_update(None, "key", passphrase)?;
+    }
+
+    migration::migrate(&mut db)?;
     Ok(db)
 }
 
+/// Inserts a row into the `transactions` ledger and returns its `tx_id`.
+fn record_transaction(
+    db: &Connection,
+    account_number: &str,
+    kind: &str,
+    amount: i64,
+    state: &str,
+    fee: i64,
+    denom: &str,
+) -> Result<i64> {
+    db.execute(
+        "INSERT INTO transactions (account_number, kind, amount, state, fee, denom) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (account_number, kind, amount, state, fee, denom),
+    )?;
+    Ok(db.last_insert_rowid())
+}
+
+fn fetch_transaction(db: &Connection, account_number: &str, tx_id: i64) -> Result<Option<Transaction>> {
+    let mut stmt = db.prepare(
+        "SELECT tx_id, account_number, kind, amount, state, fee, denom FROM transactions WHERE tx_id=?1 AND account_number=?2",
+    )?;
+
+    let tx = stmt
+        .query_row((tx_id, account_number), |row| {
+            Ok(Transaction {
+                tx_id: row.get(0)?,
+                account_number: row.get(1)?,
+                kind: row.get(2)?,
+                amount: row.get(3)?,
+                state: row.get(4)?,
+                fee: row.get(5)?,
+                denom: row.get(6)?,
+            })
+        })
+        .ok();
+
+    Ok(tx)
+}
+
+fn ensure_unlocked(db: &Connection, account_number: &str) -> Result<()> {
+    let locked: bool = db.query_row(
+        "SELECT locked FROM account WHERE account_number=?1",
+        (account_number,),
+        |row| row.get(0),
+    )?;
+
+    if locked {
+        Err(rusqlite::Error::QueryReturnedNoRows) // Account was locked by a chargeback.
+    } else {
+        Ok(())
+    }
+}
+
+fn generate_salt() -> String {
+    let mut rng = thread_rng();
+    (0..16).map(|_| format!("{:x}", rng.gen_range(0..16u8))).collect()
+}
+
+/// How many rounds of SHA-256 to chain in [`hash_pin`]. A 6-digit PIN only
+/// has 1e6 possibilities, so a single SHA-256 round is brute-forceable
+/// near-instantly per account even with a random salt (the salt only
+/// defeats cross-account rainbow tables, not per-account brute force).
+/// Chaining rounds is a poor man's KDF stretch without pulling in a new
+/// dependency (e.g. `argon2`/`pbkdf2`) for it.
+const PIN_HASH_ROUNDS: u32 = 100_000;
+
+fn hash_pin(pin: &str, salt: &str) -> String {
+    let mut digest = {
+        let mut hasher = Sha256::new();
+        hasher.update(salt.as_bytes());
+        hasher.update(pin.as_bytes());
+        hasher.finalize()
+    };
+
+    for _ in 1..PIN_HASH_ROUNDS {
+        digest = Sha256::digest(digest);
+    }
+
+    format!("{:x}", digest)
+}
+
+/// Compares two equal-meaning strings byte-for-byte without short-circuiting
+/// on the first difference, so a failed PIN check doesn't leak via timing
+/// how many leading bytes of the hash matched.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Verifies a supplied PIN against the stored salted hash, using
+/// [`constant_time_eq`] so the comparison itself doesn't leak timing info.
+fn verify_pin(db: &Connection, account_number: &str, pin: &str) -> Result<bool> {
+    let (pin_hash, salt): (String, String) = db.query_row(
+        "SELECT pin_hash, salt FROM account WHERE account_number=?1",
+        (account_number,),
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    Ok(constant_time_eq(&hash_pin(pin, &salt), &pin_hash))
+}
+
+/// Reads an account's balance in a single denomination, treating a missing
+/// `(account_number, denom)` row as a balance of `0`.
+fn get_balance(db: &Connection, account_number: &str, denom: &str) -> Result<i64> {
+    db.query_row(
+        "SELECT amount FROM balances WHERE account_number=?1 AND denom=?2",
+        (account_number, denom),
+        |row| row.get(0),
+    )
+    .or(Ok(0))
+}
+
+/// Adds `delta` (which may be negative) to an account's balance in `denom`,
+/// creating the `(account_number, denom)` row if it doesn't exist yet.
+fn adjust_balance(db: &Connection, account_number: &str, denom: &str, delta: i64) -> Result<()> {
+    db.execute(
+        "INSERT INTO balances (account_number, denom, amount) VALUES (?1, ?2, ?3)
+         ON CONFLICT(account_number, denom) DO UPDATE SET amount = amount + ?3",
+        (account_number, denom, delta),
+    )?;
+    Ok(())
+}
+
+/// Moves `amount` held by a disputed deposit into `held`, marking the tx `Disputed`.
+/// A no-op (not an error) if `tx_id` doesn't exist, doesn't belong to this account,
+/// isn't in USD (`held` predates multi-currency support and is still only
+/// tracked against the USD balance, so disputing e.g. a BTC deposit would
+/// otherwise corrupt the account's USD balance), or the account no longer
+/// holds at least `tx.amount` (funds may have already been withdrawn since
+/// the original deposit).
+pub fn dispute(account_number: &str, tx_id: i64) -> Result<()> {
+    let mut db = initialise_bankdb()?;
+
+    let tx = match fetch_transaction(&db, account_number, tx_id)? {
+        Some(tx) if tx.state == "Posted" && tx.kind == "Deposit" && tx.denom == "USD" => tx,
+        _ => return Ok(()),
+    };
+
+    if tx.amount > get_balance(&db, account_number, "USD")? {
+        return Ok(());
+    }
+
+    let sql_tx = db.transaction()?;
+    sql_tx.execute(
+        "UPDATE account SET balance = balance - ?1, held = held + ?1 WHERE account_number=?2",
+        (tx.amount, account_number),
+    )?;
+    // `get_balance`/`withdraw`/`transfer` all authorize against `balances`,
+    // not `account.held` — without this, disputed funds stay spendable
+    // through any multi-currency-aware path and the hold does nothing.
+    adjust_balance(&sql_tx, account_number, "USD", -tx.amount)?;
+    sql_tx.execute(
+        "UPDATE transactions SET state='Disputed' WHERE tx_id=?1",
+        (tx_id,),
+    )?;
+    sql_tx.commit()?;
+    Ok(())
+}
+
+/// Releases held funds from a disputed tx back to the available balance.
+pub fn resolve(account_number: &str, tx_id: i64) -> Result<()> {
+    let mut db = initialise_bankdb()?;
+
+    let tx = match fetch_transaction(&db, account_number, tx_id)? {
+        Some(tx) if tx.state == "Disputed" && tx.denom == "USD" => tx,
+        _ => return Ok(()),
+    };
+
+    let sql_tx = db.transaction()?;
+    sql_tx.execute(
+        "UPDATE account SET balance = balance + ?1, held = held - ?1 WHERE account_number=?2",
+        (tx.amount, account_number),
+    )?;
+    adjust_balance(&sql_tx, account_number, "USD", tx.amount)?;
+    sql_tx.execute(
+        "UPDATE transactions SET state='Resolved' WHERE tx_id=?1",
+        (tx_id,),
+    )?;
+    sql_tx.commit()?;
+    Ok(())
+}
+
+/// Removes held funds for good, marks the tx `Chargedback`, and locks the account.
+pub fn chargeback(account_number: &str, tx_id: i64) -> Result<()> {
+    let mut db = initialise_bankdb()?;
+
+    let tx = match fetch_transaction(&db, account_number, tx_id)? {
+        Some(tx) if tx.state == "Disputed" && tx.denom == "USD" => tx,
+        _ => return Ok(()),
+    };
+
+    let sql_tx = db.transaction()?;
+    sql_tx.execute(
+        "UPDATE account SET held = held - ?1, locked = 1 WHERE account_number=?2",
+        (tx.amount, account_number),
+    )?;
+    sql_tx.execute(
+        "UPDATE transactions SET state='Chargedback' WHERE tx_id=?1",
+        (tx_id,),
+    )?;
+    sql_tx.commit()?;
+    Ok(())
+}
+
 pub fn create_account(data: &AccountNumber, balance: u64) -> Result<Account> {
     let db = initialise_bankdb()?;
     let account_number = data.to_string();
@@ -88,53 +339,62 @@ pub fn create_account(data: &AccountNumber, balance: u64) -> Result<Account> {
     }
 
     let pin: String = pin.into_iter().collect();
+    let salt = generate_salt();
+    let pin_hash = hash_pin(&pin, &salt);
 
     let new_account = Account {
         id: newest_max_id,
         account_number,
         balance: balance.try_into().unwrap(), // Convert u64 to usize
+        held: 0,
+        locked: false,
         pin,
     };
 
     db.execute(
-        "INSERT INTO account (id, account_number, pin, balance) VALUES (?1, ?2, ?3, ?4)",
+        "INSERT INTO account (id, account_number, pin_hash, salt, balance) VALUES (?1, ?2, ?3, ?4, ?5)",
         (
             &new_account.id,
             &new_account.account_number,
-            &new_account.pin,
+            &pin_hash,
+            &salt,
             &new_account.balance,
         ),
     )?;
     Ok(new_account)
 }
 
-pub fn deposit(amount: &str, pin: &str, account_number: &str) -> Result<()> {
-    let db = initialise_bankdb()?;
-    let query_string = format!(
-        "SELECT pin FROM account where account_number='{}';",
-        account_number
-    );
+pub fn deposit(amount: &str, pin: &str, account_number: &str, denom: &str) -> Result<()> {
+    let mut db = initialise_bankdb()?;
+    let correct_pin = verify_pin(&db, account_number, pin)?;
 
-    let pin_from_db: String = db.query_row(&query_string, [], |row| row.get(0))?;
+    if correct_pin {
+        let parsed_amount: i64 = amount.parse().map_err(|_| rusqlite::Error::QueryReturnedNoRows)?;
 
-    let correct_pin = { pin_from_db == pin };
+        let tx = db.transaction()?;
+        ensure_unlocked(&tx, account_number)?;
 
-    if correct_pin {
-        db.execute(
-            "UPDATE account SET balance = balance + ?1 WHERE account_number=?2",
-            (amount, account_number),
-        )?;
+        adjust_balance(&tx, account_number, denom, parsed_amount)?;
 
-        let query_string = format!(
-            "SELECT balance FROM account where account_number='{}';",
-            account_number
-        );
+        // `account.balance` predates multi-currency support and is what
+        // dispute/resolve/chargeback still act on, so it's kept mirroring
+        // the USD balance rather than reworking held amounts per denom.
+        if denom == "USD" {
+            tx.execute(
+                "UPDATE account SET balance = balance + ?1 WHERE account_number=?2",
+                (amount, account_number),
+            )?;
+        }
+
+        record_transaction(&tx, account_number, "Deposit", parsed_amount, "Posted", 0, denom)?;
+
+        tx.commit()?;
 
-        let amount_from_db: usize = db.query_row(&query_string, [], |row| row.get(0))?;
+        let amount_from_db = get_balance(&db, account_number, denom)?;
 
         println!(
-            "The account number `{}` now has a balance of `{}`.\n",
-            &account_number, &amount_from_db
+            "The account number `{}` now has a {} balance of `{}`.\n",
+            &account_number, denom, &amount_from_db
         );
     } else {
         eprintln!("Wrong pin. Try again...");
@@ -142,11 +402,18 @@ pub fn deposit(amount: &str, pin: &str, account_number: &str) -> Result<()> {
     Ok(())
 }
 
+/// Moves `amount` of `denom` from `origin_account` to `target_account`. Both
+/// accounts keep their balance in the same denomination on each side — this
+/// never converts between currencies. `fee` (pass `0` for none) is debited
+/// from the origin on top of `amount` but never credited to the target, and
+/// is recorded alongside the origin's ledger entry.
 pub fn transfer(
     amount: &str,
     pin: &str,
     origin_account: &str,
     target_account: &str,
+    denom: &str,
+    fee: i64,
 ) -> Result<(Account, Account)> {
     if *origin_account == *target_account {
         return Err(rusqlite::Error::QueryReturnedNoRows); // Makes sense. We haven't returned any.
@@ -155,28 +422,53 @@ pub fn transfer(
     let origin_account = fetch_account(origin_account)?;
     let target_account = fetch_account(target_account)?;
 
-    let correct_pin = origin_account.pin == pin;
+    let correct_pin = verify_pin(&initialise_bankdb()?, &origin_account.account_number, pin)?;
 
     if correct_pin {
         let amount = amount
-            .parse::<u64>().map_err(|_| {
+            .parse::<i64>().map_err(|_| {
                 rusqlite::Error::QueryReturnedNoRows
             })?;
 
-        if amount > origin_account.balance as u64 {
+        if amount < 0 || fee < 0 {
             return Err(rusqlite::Error::QueryReturnedNoRows);
-        } else {
-            let db = initialise_bankdb()?;
-            db.execute(
+        }
+
+        let debit = amount + fee;
+
+        // Single connection + transaction: the balance check and both updates
+        // either all land together or the transaction rolls back untouched.
+        let mut db = initialise_bankdb()?;
+        let tx = db.transaction()?;
+
+        ensure_unlocked(&tx, &origin_account.account_number)?;
+        ensure_unlocked(&tx, &target_account.account_number)?;
+
+        let current_balance = get_balance(&tx, &origin_account.account_number, denom)?;
+
+        if debit > current_balance {
+            return Err(rusqlite::Error::QueryReturnedNoRows); // tx drops here and rolls back
+        }
+
+        adjust_balance(&tx, &target_account.account_number, denom, amount)?;
+        adjust_balance(&tx, &origin_account.account_number, denom, -debit)?;
+
+        if denom == "USD" {
+            tx.execute(
                 "UPDATE account SET balance = balance + ?1 WHERE account_number=?2",
                 (amount, &target_account.account_number),
             )?;
 
-            db.execute(
+            tx.execute(
                 "UPDATE account SET balance = balance - ?1 WHERE account_number=?2",
-                (amount, &origin_account.account_number),
+                (debit, &origin_account.account_number),
             )?;
-        };
+        }
+
+        record_transaction(&tx, &target_account.account_number, "TransferIn", amount, "Posted", 0, denom)?;
+        record_transaction(&tx, &origin_account.account_number, "TransferOut", amount, "Posted", fee, denom)?;
+
+        tx.commit()?;
     } else {
         return Err(rusqlite::Error::QueryReturnedNoRows);
     }
@@ -187,54 +479,49 @@ pub fn transfer(
     Ok((origin_account, target_account))
 }
 
-pub fn withdraw(amount: &str, pin: &str, account_number: &str) -> Result<()> {
-    let db = initialise_bankdb()?;
-    let query_string = format!(
-        "SELECT pin FROM account where account_number='{}';",
-        account_number
-    );
-
-    let pin_from_db: String = db.query_row(&query_string, [], |row| row.get(0))?;
-
-    let correct_pin = { pin_from_db == pin };
+pub fn withdraw(amount: &str, pin: &str, account_number: &str, denom: &str) -> Result<()> {
+    let mut db = initialise_bankdb()?;
+    let correct_pin = verify_pin(&db, account_number, pin)?;
 
     if correct_pin {
-        let query_string = format!(
-            "SELECT balance FROM account where account_number='{}';",
-            account_number
-        );
-
-        let amount_from_db: usize = db.query_row(&query_string, [], |row| row.get(0))?;
+        let amount_from_db = get_balance(&db, account_number, denom)?;
 
         println!(
-            "The account number `{}` has a balance of `{}`.\n",
-            &account_number, &amount_from_db
+            "The account number `{}` has a {} balance of `{}`.\n",
+            &account_number, denom, &amount_from_db
         );
 
-        let amount = amount
-            .parse::<usize>()
-            .expect("Not able to parse string to usize");
+        let amount: i64 = amount
+            .parse()
+            .expect("Not able to parse string to i64");
+        assert!(amount >= 0, "withdrawal amount must not be negative");
 
         if amount > amount_from_db {
             eprintln!(
                 "You are trying to withdraw an amount that exceeds your current deposit... aborting...\n"
             );
         } else {
-            db.execute(
-                "UPDATE account SET balance = balance - ?1 WHERE account_number=?2",
-                (amount, account_number),
-            )?;
+            let tx = db.transaction()?;
+            ensure_unlocked(&tx, account_number)?;
 
-            let query_string = format!(
-                "SELECT balance FROM account where account_number='{}';",
-                account_number
-            );
+            adjust_balance(&tx, account_number, denom, -amount)?;
+
+            if denom == "USD" {
+                tx.execute(
+                    "UPDATE account SET balance = balance - ?1 WHERE account_number=?2",
+                    (amount, account_number),
+                )?;
+            }
+
+            record_transaction(&tx, account_number, "Withdraw", amount, "Posted", 0, denom)?;
 
-            let amount_from_db: usize = db.query_row(&query_string, [], |row| row.get(0))?;
+            tx.commit()?;
+
+            let amount_from_db = get_balance(&db, account_number, denom)?;
 
             println!(
-                "The account number `{}` now has a balance of `{}`.\n",
-                &account_number, &amount_from_db
+                "The account number `{}` now has a {} balance of `{}`.\n",
+                &account_number, denom, &amount_from_db
             );
         };
     } else {
@@ -245,13 +532,7 @@ pub fn withdraw(amount: &str, pin: &str, account_number: &str) -> Result<()> {
 
 pub fn delete_account(account_number: &str, pin: &str) -> Result<()> {
     let db = initialise_bankdb()?;
-    let query_string = format!(
-        "SELECT pin FROM account where account_number='{}';",
-        &account_number
-    );
-
-    let pin_from_db: String = db.query_row(&query_string, [], |row| row.get(0))?;
-    let correct_pin = { pin_from_db == pin };
+    let correct_pin = verify_pin(&db, account_number, pin)?;
 
     if correct_pin {
         db.execute(
@@ -265,31 +546,104 @@ pub fn delete_account(account_number: &str, pin: &str) -> Result<()> {
     Ok(())
 }
 
+/// Prints every non-zero denomination this account holds.
 pub fn show_balance(account_number: &str) -> Result<()> {
     let db = initialise_bankdb()?;
-    let query_string = format!(
-        "SELECT balance FROM account where account_number='{}';",
-        account_number
-    );
+    let mut stmt = db.prepare(
+        "SELECT denom, amount FROM balances WHERE account_number=?1 AND amount != 0",
+    )?;
 
-    let amount_from_db: usize = db.query_row(&query_string, [], |row| row.get(0))?;
+    let balances = stmt.query_map((account_number,), |row| {
+        Ok((row.get::<usize, String>(0)?, row.get::<usize, i64>(1)?))
+    })?;
 
-    println!(
-        "The account number `{}` now has a balance of `{}`.\n",
-        &account_number, &amount_from_db
-    );
+    for balance in balances {
+        let (denom, amount) = balance?;
+        println!(
+            "The account number `{}` now has a {} balance of `{}`.\n",
+            &account_number, denom, amount
+        );
+    }
     Ok(())
 }
 
+/// Sums an account's transaction history in a given `denom` into a single net
+/// figure: credits (`Deposit`, `TransferIn`) minus debits (`Withdraw`,
+/// `TransferOut`) minus every fee charged along the way. Lets a statement
+/// reconcile the account's current balance in that currency against what its
+/// recorded activity says it should be.
+pub fn net_value(account_number: &str, denom: &str) -> Result<i64> {
+    let db = initialise_bankdb()?;
+    let mut stmt = db.prepare(
+        "SELECT kind, amount, fee FROM transactions WHERE account_number=?1 AND denom=?2",
+    )?;
+
+    let entries = stmt.query_map((account_number, denom), |row| {
+        Ok((
+            row.get::<usize, String>(0)?,
+            row.get::<usize, i64>(1)?,
+            row.get::<usize, i64>(2)?,
+        ))
+    })?;
+
+    let mut net = 0i64;
+    for entry in entries {
+        let (kind, amount, fee) = entry?;
+        match kind.as_str() {
+            "Deposit" | "TransferIn" => net += amount,
+            "Withdraw" | "TransferOut" => net -= amount,
+            _ => {}
+        }
+        net -= fee;
+    }
+
+    Ok(net)
+}
+
+/// Snapshots `src` into the database at `path`, page-by-page, via SQLite's
+/// online backup API.
+fn backup_connection_to(src: &Connection, path: &Path) -> Result<()> {
+    let mut dest = Connection::open(path)?;
+    let backup = rusqlite::backup::Backup::new(src, &mut dest)?;
+    backup.run_to_completion(5, Duration::from_millis(250), None)?;
+    Ok(())
+}
+
+/// Overwrites `dest` with the snapshot at `path`, page-by-page, via SQLite's
+/// online backup API.
+fn restore_connection_from(dest: &mut Connection, path: &Path) -> Result<()> {
+    let src = Connection::open(path)?;
+    let backup = rusqlite::backup::Backup::new(&src, dest)?;
+    backup.run_to_completion(5, Duration::from_millis(250), None)?;
+    Ok(())
+}
+
+/// Takes a consistent, page-by-page snapshot of the live database using
+/// SQLite's online backup API, rather than copying `bank.s3db` on the
+/// filesystem (which could capture a half-written transaction).
+pub fn backup_to(path: &Path) -> Result<()> {
+    let db = initialise_bankdb()?;
+    backup_connection_to(&db, path)
+}
+
+/// Restores `bank.s3db` from a snapshot previously written by [`backup_to`],
+/// again page-by-page via the online backup API.
+pub fn restore_from(path: &Path) -> Result<()> {
+    let mut db = initialise_bankdb()?;
+    restore_connection_from(&mut db, path)
+}
+
 fn fetch_account(account: &str) -> Result<Account> {
     let db = initialise_bankdb()?;
-    let mut stmt = db.prepare("SELECT id, account_number, balance, pin FROM account")?;
+    let mut stmt = db.prepare("SELECT id, account_number, balance, held, locked FROM account")?;
     let accounts = stmt.query_map([], |row| {
         Ok(Account {
             id: row.get(0)?,
             account_number: row.get(1)?,
             balance: row.get(2)?,
-            pin: row.get(3)?,
+            held: row.get(3)?,
+            locked: row.get(4)?,
+            pin: String::new(), // no plaintext PIN is ever stored to hand back here
         })
     })?;
 
@@ -322,16 +676,17 @@ mod tests {
         let origin_account = Account::new()?;
         let target_account = Account::new()?;
         let deposit_balance = "10000";
+        let origin_pin = origin_account.pin.clone(); // only ever shown once, at creation
 
         // Deposit into the origin account
-        deposit(deposit_balance, &origin_account.pin, &origin_account.account_number)?;
+        deposit(deposit_balance, &origin_pin, &origin_account.account_number, "USD")?;
 
         // Fetch the updated origin account to get the new balance
         let origin_account = fetch_account(&origin_account.account_number)?;
         assert_eq!(*deposit_balance, origin_account.balance.to_string());
 
         // Step 2: Transfer the entire balance from origin account to target account
-        transfer(deposit_balance, &origin_account.pin, &origin_account.account_number, &target_account.account_number)?;
+        transfer(deposit_balance, &origin_pin, &origin_account.account_number, &target_account.account_number, "USD", 0)?;
 
         // Fetch updated account balances after transfer
         let origin_account = fetch_account(&origin_account.account_number)?;
@@ -343,5 +698,281 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn failed_transfer_rolls_back_both_balances() -> Result<()> {
+        let origin_account = Account::new()?;
+        let target_account = Account::new()?;
+        let deposit_balance = "100";
+
+        deposit(deposit_balance, &origin_account.pin, &origin_account.account_number, "USD")?;
+
+        // Ask for more than the origin actually holds, so the balance check
+        // inside the transaction fails and the whole thing rolls back.
+        let result = transfer(
+            "100000",
+            &origin_account.pin,
+            &origin_account.account_number,
+            &target_account.account_number,
+            "USD",
+            0,
+        );
+        assert!(result.is_err());
+
+        let origin_account = fetch_account(&origin_account.account_number)?;
+        let target_account = fetch_account(&target_account.account_number)?;
+
+        assert_eq!(deposit_balance.to_owned(), origin_account.balance.to_string());
+        assert_eq!("0".to_string(), target_account.balance.to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn disputed_deposit_can_be_resolved() -> Result<()> {
+        let account = Account::new()?;
+        deposit("500", &account.pin, &account.account_number, "USD")?;
+
+        let db = initialise_bankdb()?;
+        let tx_id: i64 = db.query_row(
+            "SELECT MAX(tx_id) FROM transactions WHERE account_number=?1",
+            (&account.account_number,),
+            |row| row.get(0),
+        )?;
+
+        dispute(&account.account_number, tx_id)?;
+        let disputed = fetch_account(&account.account_number)?;
+        assert_eq!(disputed.balance, 0);
+        assert_eq!(disputed.held, 500);
+
+        resolve(&account.account_number, tx_id)?;
+        let resolved = fetch_account(&account.account_number)?;
+        assert_eq!(resolved.balance, 500);
+        assert_eq!(resolved.held, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disputed_funds_cannot_be_withdrawn() -> Result<()> {
+        let account = Account::new()?;
+        deposit("500", &account.pin, &account.account_number, "USD")?;
+
+        let db = initialise_bankdb()?;
+        let tx_id: i64 = db.query_row(
+            "SELECT MAX(tx_id) FROM transactions WHERE account_number=?1",
+            (&account.account_number,),
+            |row| row.get(0),
+        )?;
+
+        dispute(&account.account_number, tx_id)?;
+        assert_eq!(account.balance_of("USD")?, 0); // held, not available
+
+        withdraw("500", &account.pin, &account.account_number, "USD")?;
+        assert_eq!(account.balance_of("USD")?, 0); // withdrawal rejected, balance unchanged
+
+        Ok(())
+    }
+
+    #[test]
+    fn disputing_an_already_spent_deposit_is_a_no_op() -> Result<()> {
+        let account = Account::new()?;
+        deposit("500", &account.pin, &account.account_number, "USD")?;
+
+        let db = initialise_bankdb()?;
+        let deposit_tx_id: i64 = db.query_row(
+            "SELECT MAX(tx_id) FROM transactions WHERE account_number=?1 AND kind='Deposit'",
+            (&account.account_number,),
+            |row| row.get(0),
+        )?;
+
+        withdraw("500", &account.pin, &account.account_number, "USD")?;
+        assert_eq!(account.balance_of("USD")?, 0);
+
+        // The deposited funds are already gone — disputing should be a no-op
+        // rather than driving the balance negative.
+        dispute(&account.account_number, deposit_tx_id)?;
+        let unchanged = fetch_account(&account.account_number)?;
+        assert_eq!(unchanged.balance, 0);
+        assert_eq!(unchanged.held, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disputing_a_withdrawal_is_a_no_op() -> Result<()> {
+        let account = Account::new()?;
+        deposit("500", &account.pin, &account.account_number, "USD")?;
+        withdraw("200", &account.pin, &account.account_number, "USD")?;
+
+        let db = initialise_bankdb()?;
+        let tx_id: i64 = db.query_row(
+            "SELECT MAX(tx_id) FROM transactions WHERE account_number=?1 AND kind='Withdraw'",
+            (&account.account_number,),
+            |row| row.get(0),
+        )?;
+
+        dispute(&account.account_number, tx_id)?;
+        let unchanged = fetch_account(&account.account_number)?;
+        assert_eq!(unchanged.balance, 300);
+        assert_eq!(unchanged.held, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disputing_a_non_usd_deposit_is_a_no_op() -> Result<()> {
+        let account = Account::new()?;
+        deposit("3", &account.pin, &account.account_number, "BTC")?;
+
+        let db = initialise_bankdb()?;
+        let tx_id: i64 = db.query_row(
+            "SELECT MAX(tx_id) FROM transactions WHERE account_number=?1 AND kind='Deposit'",
+            (&account.account_number,),
+            |row| row.get(0),
+        )?;
+
+        dispute(&account.account_number, tx_id)?;
+        let unchanged = fetch_account(&account.account_number)?;
+        assert_eq!(unchanged.balance, 0);
+        assert_eq!(unchanged.held, 0);
+        assert_eq!(account.balance_of("BTC")?, 3); // untouched: `held` only tracks USD
+
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_locks_the_account() -> Result<()> {
+        let account = Account::new()?;
+        deposit("300", &account.pin, &account.account_number, "USD")?;
+
+        let db = initialise_bankdb()?;
+        let tx_id: i64 = db.query_row(
+            "SELECT MAX(tx_id) FROM transactions WHERE account_number=?1",
+            (&account.account_number,),
+            |row| row.get(0),
+        )?;
+
+        dispute(&account.account_number, tx_id)?;
+        chargeback(&account.account_number, tx_id)?;
+
+        let locked_account = fetch_account(&account.account_number)?;
+        assert!(locked_account.locked);
+        assert_eq!(locked_account.held, 0);
+
+        assert!(deposit("100", &account.pin, &account.account_number, "USD").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn balances_are_tracked_per_denomination() -> Result<()> {
+        let account = Account::new()?;
+
+        deposit("50", &account.pin, &account.account_number, "USD")?;
+        deposit("3", &account.pin, &account.account_number, "BTC")?;
+
+        assert_eq!(account.balance_of("USD")?, 50);
+        assert_eq!(account.balance_of("BTC")?, 3);
+        assert_eq!(account.balance_of("EUR")?, 0);
+
+        withdraw("20", &account.pin, &account.account_number, "USD")?;
+        assert_eq!(account.balance_of("USD")?, 30);
+        assert_eq!(account.balance_of("BTC")?, 3); // untouched by the USD withdrawal
+
+        Ok(())
+    }
+
+    #[test]
+    fn transfer_fee_is_debited_from_origin_and_recorded() -> Result<()> {
+        let origin_account = Account::new()?;
+        let target_account = Account::new()?;
+
+        deposit("1000", &origin_account.pin, &origin_account.account_number, "USD")?;
+
+        transfer(
+            "100",
+            &origin_account.pin,
+            &origin_account.account_number,
+            &target_account.account_number,
+            "USD",
+            5,
+        )?;
+
+        assert_eq!(origin_account.balance_of("USD")?, 1000 - 100 - 5);
+        assert_eq!(target_account.balance_of("USD")?, 100);
+
+        // net value = +1000 deposited, -100 sent, -5 fee
+        assert_eq!(net_value(&origin_account.account_number, "USD")?, 1000 - 100 - 5);
+        assert_eq!(net_value(&target_account.account_number, "USD")?, 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn net_value_does_not_mix_denominations() -> Result<()> {
+        let account = Account::new()?;
+
+        deposit("50", &account.pin, &account.account_number, "USD")?;
+        deposit("3", &account.pin, &account.account_number, "BTC")?;
+        withdraw("20", &account.pin, &account.account_number, "USD")?;
+
+        assert_eq!(net_value(&account.account_number, "USD")?, 50 - 20);
+        assert_eq!(net_value(&account.account_number, "BTC")?, 3);
+        assert_eq!(net_value(&account.account_number, "EUR")?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn restore_from_brings_back_the_balance_at_backup_time() -> Result<()> {
+        // `backup_to`/`restore_from` operate on the single shared
+        // `mock_bank.s3db` (`database_path()` under `#[cfg(test)]`), and
+        // `cargo test` runs tests concurrently, so exercising the real
+        // functions here would stomp every other test's in-flight accounts.
+        // Drive the same backup-API logic against a throwaway connection
+        // instead, via the private helpers the public functions delegate to.
+        let mut live = Connection::open_in_memory()?;
+        migration::migrate(&mut live)?;
+        live.execute(
+            "INSERT INTO account (account_number, balance) VALUES ('TEST-ACCT', 500)",
+            (),
+        )?;
+
+        let snapshot_path = std::env::temp_dir().join("mock_bank_backup_test.s3db");
+        let _ = std::fs::remove_file(&snapshot_path);
+        backup_connection_to(&live, &snapshot_path)?;
+
+        // Diverge from the snapshot so restoring is actually observable.
+        live.execute(
+            "UPDATE account SET balance = balance + 250 WHERE account_number='TEST-ACCT'",
+            (),
+        )?;
+        let balance: i64 = live.query_row(
+            "SELECT balance FROM account WHERE account_number='TEST-ACCT'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(balance, 750);
+
+        restore_connection_from(&mut live, &snapshot_path)?;
+        let balance: i64 = live.query_row(
+            "SELECT balance FROM account WHERE account_number='TEST-ACCT'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(balance, 500);
+
+        std::fs::remove_file(&snapshot_path).ok();
+        Ok(())
+    }
+
+    #[test]
+    fn encryption_key_is_rejected_without_sqlcipher() {
+        // This test binary is built against stock SQLite, where `PRAGMA key`
+        // is a silent no-op — requesting it should fail loudly rather than
+        // hand back a connection that looks encrypted but isn't.
+        assert!(initialise_bankdb_with_key(Some("hunter2")).is_err());
+    }
 }
 
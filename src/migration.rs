@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: Unlicense
+
+//! Versioned schema migrations for `bank.s3db`.
+//!
+//! Each migration is a plain function that takes the open `Connection` and
+//! applies one schema step. `migrate()` reads `PRAGMA user_version`, runs
+//! only the migrations whose 1-based position in `MIGRATIONS` is greater
+//! than that version (each inside its own transaction), and bumps
+//! `user_version` to that position as it goes. This
+//! lets `initialise_bankdb()` upgrade an existing `bank.s3db` (or the test
+//! `mock_bank.s3db`) in place instead of assuming a fresh file.
+
+use rusqlite::{Connection, Result};
+
+type Migration = fn(&Connection) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    create_account_table,
+    create_transactions_table,
+    add_held_and_locked_columns,
+    add_pin_hash_and_salt_columns,
+    create_balances_table,
+    add_fee_column_to_transactions,
+    add_denom_column_to_transactions,
+];
+
+fn create_account_table(db: &Connection) -> Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS account(
+id INTEGER PRIMARY KEY,
+account_number TEXT,
+pin TEXT DEFAULT '000000',
+balance INTEGER DEFAULT 0
+)",
+        (),
+    )?;
+    Ok(())
+}
+
+fn create_transactions_table(db: &Connection) -> Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS transactions(
+tx_id INTEGER PRIMARY KEY AUTOINCREMENT,
+account_number TEXT NOT NULL,
+kind TEXT NOT NULL,
+amount INTEGER NOT NULL,
+state TEXT NOT NULL
+)",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Runs an `ALTER TABLE ... ADD COLUMN` statement, treating "duplicate
+/// column name" as success, so replaying these migrations against an
+/// `account` table that already has the column while `user_version` is
+/// still behind does not fail.
+fn add_column_if_missing(db: &Connection, ddl: &str) -> Result<()> {
+    match db.execute(ddl, ()) {
+        Ok(_) => Ok(()),
+        Err(rusqlite::Error::SqliteFailure(_, Some(msg)))
+            if msg.contains("duplicate column name") =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn add_held_and_locked_columns(db: &Connection) -> Result<()> {
+    add_column_if_missing(db, "ALTER TABLE account ADD COLUMN held INTEGER DEFAULT 0")?;
+    add_column_if_missing(db, "ALTER TABLE account ADD COLUMN locked INTEGER DEFAULT 0")?;
+    Ok(())
+}
+
+fn add_pin_hash_and_salt_columns(db: &Connection) -> Result<()> {
+    add_column_if_missing(db, "ALTER TABLE account ADD COLUMN pin_hash TEXT")?;
+    add_column_if_missing(db, "ALTER TABLE account ADD COLUMN salt TEXT")?;
+    Ok(())
+}
+
+fn create_balances_table(db: &Connection) -> Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS balances(
+account_number TEXT NOT NULL,
+denom TEXT NOT NULL,
+amount INTEGER NOT NULL DEFAULT 0,
+PRIMARY KEY (account_number, denom)
+)",
+        (),
+    )?;
+    Ok(())
+}
+
+fn add_fee_column_to_transactions(db: &Connection) -> Result<()> {
+    add_column_if_missing(
+        db,
+        "ALTER TABLE transactions ADD COLUMN fee INTEGER NOT NULL DEFAULT 0",
+    )
+}
+
+/// Records which currency a ledger entry moved. Existing rows predate
+/// multi-currency support and were always USD, hence the default.
+fn add_denom_column_to_transactions(db: &Connection) -> Result<()> {
+    add_column_if_missing(
+        db,
+        "ALTER TABLE transactions ADD COLUMN denom TEXT NOT NULL DEFAULT 'USD'",
+    )
+}
+
+/// Brings `db` up to the latest schema version, applying only the
+/// migrations it hasn't already seen.
+pub fn migrate(db: &mut Connection) -> Result<()> {
+    let current_version: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate() {
+        let version = index as i64 + 1;
+        if version <= current_version {
+            continue;
+        }
+
+        let tx = db.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_tolerates_columns_added_by_the_old_ad_hoc_alter_tables() -> Result<()> {
+        let mut db = Connection::open_in_memory()?;
+
+        // A database whose `account` table already has
+        // `held`/`locked`/`pin_hash`/`salt`, but whose `user_version` is
+        // still 0.
+        db.execute(
+            "CREATE TABLE account(
+id INTEGER PRIMARY KEY,
+account_number TEXT,
+pin TEXT DEFAULT '000000',
+balance INTEGER DEFAULT 0,
+held INTEGER DEFAULT 0,
+locked INTEGER DEFAULT 0,
+pin_hash TEXT,
+salt TEXT
+)",
+            (),
+        )?;
+
+        migrate(&mut db)?;
+
+        let version: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version, MIGRATIONS.len() as i64);
+
+        Ok(())
+    }
+}